@@ -0,0 +1,399 @@
+//! A minimal, read-only IMAP4rev1 server exposing a `MailStore`.
+//!
+//! This lets test suites already wired for IMAP clients (e.g. `rust-imap`)
+//! fetch trapped mail without knowing trapmail's on-disk format. Only the
+//! handful of commands needed to browse a mailbox read-only are supported:
+//! `LOGIN` (accepts any credentials), `SELECT INBOX`, `FETCH`, `SEARCH`,
+//! `UID FETCH`/`UID SEARCH`, and `LOGOUT`.
+
+use crate::{Error, Mail, MailStore, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+
+/// A mail together with the UID it was assigned for this session.
+///
+/// UIDs are assigned sequentially from `iter_mails`' (timestamp-sorted)
+/// order, so they stay stable across sessions as long as no mail is removed
+/// from the store.
+struct UidMail {
+    uid: u32,
+    mail: Mail,
+}
+
+/// Serves `store` as a read-only IMAP4rev1 mailbox on `addr`.
+///
+/// Spawns one thread per connection; never returns unless binding `addr`,
+/// accepting a connection, or loading the store's mail fails.
+pub fn serve<A: ToSocketAddrs>(store: MailStore, addr: A) -> Result<()> {
+    let listener = TcpListener::bind(addr).map_err(Error::Server)?;
+
+    for stream in listener.incoming() {
+        let stream = stream.map_err(Error::Server)?;
+        let mails = load_mails(&store)?;
+
+        thread::spawn(move || {
+            if let Err(err) = Session::new(stream).run(&mails) {
+                eprintln!("imap session ended: {}", err);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Load every mail in `store`, assigning UIDs by timestamp order.
+fn load_mails(store: &MailStore) -> Result<Vec<UidMail>> {
+    store
+        .iter_mails()?
+        .enumerate()
+        .map(|(i, mail)| mail.map(|mail| UidMail { uid: i as u32 + 1, mail }))
+        .collect()
+}
+
+/// A single client connection.
+struct Session {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    selected: bool,
+}
+
+impl Session {
+    fn new(stream: TcpStream) -> Self {
+        let writer = stream.try_clone().expect("failed to clone IMAP client socket");
+        Session {
+            reader: BufReader::new(stream),
+            writer,
+            selected: false,
+        }
+    }
+
+    fn run(&mut self, mails: &[UidMail]) -> Result<()> {
+        self.respond("* OK trapmail IMAP4rev1 ready")?;
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let n = self.reader.read_line(&mut line).map_err(Error::Server)?;
+            if n == 0 {
+                return Ok(());
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut words = line.splitn(3, ' ');
+            let tag = words.next().unwrap_or("*");
+            let mut command = words.next().unwrap_or("").to_ascii_uppercase();
+            let mut rest = words.next().unwrap_or("").to_owned();
+
+            // `UID FETCH ...`/`UID SEARCH ...`: the real command and its
+            // arguments follow the `UID` token. This is how `rust-imap`'s
+            // `uid_fetch`/`uid_search` address messages by their stable UID
+            // instead of their (session-local) sequence number.
+            let mut uid_mode = false;
+            if command == "UID" {
+                uid_mode = true;
+                let mut uid_rest = rest.splitn(2, ' ');
+                command = uid_rest.next().unwrap_or("").to_ascii_uppercase();
+                rest = uid_rest.next().unwrap_or("").to_owned();
+            }
+            let rest = rest.as_str();
+
+            let logged_out = match command.as_str() {
+                "LOGIN" => {
+                    self.respond(&format!("{} OK LOGIN completed", tag))?;
+                    false
+                }
+                "SELECT" => {
+                    self.handle_select(tag, rest, mails)?;
+                    false
+                }
+                "FETCH" => {
+                    self.handle_fetch(tag, rest, mails, uid_mode)?;
+                    false
+                }
+                "SEARCH" => {
+                    self.handle_search(tag, rest, mails, uid_mode)?;
+                    false
+                }
+                "LOGOUT" => {
+                    self.respond("* BYE trapmail IMAP4rev1 server signing off")?;
+                    self.respond(&format!("{} OK LOGOUT completed", tag))?;
+                    true
+                }
+                "NOOP" => {
+                    self.respond(&format!("{} OK NOOP completed", tag))?;
+                    false
+                }
+                other => {
+                    self.respond(&format!("{} BAD unknown command {}", tag, other))?;
+                    false
+                }
+            };
+
+            if logged_out {
+                return Ok(());
+            }
+        }
+    }
+
+    fn handle_select(&mut self, tag: &str, mailbox: &str, mails: &[UidMail]) -> Result<()> {
+        if !mailbox.trim().eq_ignore_ascii_case("INBOX") {
+            self.respond(&format!("{} NO no such mailbox", tag))?;
+            return Ok(());
+        }
+
+        self.selected = true;
+        self.respond("* FLAGS (\\Seen \\Answered \\Flagged \\Deleted \\Draft)")?;
+        self.respond(&format!("* {} EXISTS", mails.len()))?;
+        self.respond("* 0 RECENT")?;
+        self.respond("* OK [UIDVALIDITY 1] UIDs valid")?;
+        if let Some(first) = mails.first() {
+            self.respond(&format!("* OK [UIDNEXT {}] predicted next UID", first.uid + mails.len() as u32))?;
+        }
+        self.respond(&format!("{} OK [READ-ONLY] SELECT completed", tag))?;
+        Ok(())
+    }
+
+    fn handle_fetch(&mut self, tag: &str, rest: &str, mails: &[UidMail], uid_mode: bool) -> Result<()> {
+        if !self.selected {
+            self.respond(&format!("{} BAD no mailbox selected", tag))?;
+            return Ok(());
+        }
+
+        let mut parts = rest.splitn(2, ' ');
+        let seq_set = parts.next().unwrap_or("");
+        let items = parts.next().unwrap_or("").to_ascii_uppercase();
+
+        let want_envelope = items.contains("ENVELOPE");
+        let want_body = items.contains("BODY[]");
+        let want_flags = items.contains("FLAGS");
+
+        // UIDs are assigned 1..=mails.len() in the same order as sequence
+        // numbers, so the same range/set syntax and upper bound apply to
+        // `UID FETCH`'s UID set as to a plain FETCH's sequence set.
+        for (seq, entry) in mails.iter().enumerate() {
+            let seq_num = seq as u32 + 1;
+            let selector = if uid_mode { entry.uid } else { seq_num };
+            if !seq_matches(seq_set, selector, mails.len() as u32) {
+                continue;
+            }
+
+            let mut fields = Vec::new();
+            if want_envelope {
+                fields.push(format!("ENVELOPE {}", envelope(&entry.mail)));
+            }
+            if want_flags {
+                fields.push("FLAGS ()".to_owned());
+            }
+            fields.push(format!("UID {}", entry.uid));
+
+            write!(self.writer, "* {} FETCH (", seq_num).map_err(Error::Server)?;
+            self.writer
+                .write_all(fields.join(" ").as_bytes())
+                .map_err(Error::Server)?;
+            if want_body {
+                if !fields.is_empty() {
+                    self.writer.write_all(b" ").map_err(Error::Server)?;
+                }
+                // `raw_body` may not be valid UTF-8; write it as raw bytes
+                // so the declared literal length always matches what's
+                // actually sent.
+                write!(self.writer, "BODY[] {{{}}}\r\n", entry.mail.raw_body.len())
+                    .map_err(Error::Server)?;
+                self.writer.write_all(&entry.mail.raw_body).map_err(Error::Server)?;
+            }
+            self.writer.write_all(b")\r\n").map_err(Error::Server)?;
+            self.writer.flush().map_err(Error::Server)?;
+        }
+
+        self.respond(&format!("{} OK FETCH completed", tag))?;
+        Ok(())
+    }
+
+    fn handle_search(&mut self, tag: &str, rest: &str, mails: &[UidMail], uid_mode: bool) -> Result<()> {
+        if !self.selected {
+            self.respond(&format!("{} BAD no mailbox selected", tag))?;
+            return Ok(());
+        }
+
+        let matches = search(rest, mails);
+        let ids: Vec<String> = matches
+            .iter()
+            .map(|&idx| {
+                if uid_mode {
+                    mails[idx].uid.to_string()
+                } else {
+                    (idx as u32 + 1).to_string()
+                }
+            })
+            .collect();
+        self.respond(&format!("* SEARCH {}", ids.join(" ")))?;
+        self.respond(&format!("{} OK SEARCH completed", tag))?;
+        Ok(())
+    }
+
+    fn respond(&mut self, line: &str) -> Result<()> {
+        self.writer.write_all(line.as_bytes()).map_err(Error::Server)?;
+        self.writer.write_all(b"\r\n").map_err(Error::Server)?;
+        self.writer.flush().map_err(Error::Server)
+    }
+}
+
+/// Whether sequence number `seq` (out of `total`) is contained in a
+/// comma-separated set of numbers, ranges (`a:b`), or `*`-terminated
+/// ranges (`a:*`).
+fn seq_matches(seq_set: &str, seq: u32, total: u32) -> bool {
+    for part in seq_set.split(',') {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        if let Some((lo, hi)) = part.split_once(':') {
+            let lo: u32 = lo.parse().unwrap_or(1);
+            let hi: u32 = if hi == "*" { total } else { hi.parse().unwrap_or(total) };
+            if seq >= lo && seq <= hi {
+                return true;
+            }
+        } else if part == "*" {
+            if seq == total {
+                return true;
+            }
+        } else if let Ok(n) = part.parse::<u32>() {
+            if n == seq {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Very small `SEARCH` criteria matcher: `ALL` plus `SUBJECT`/`FROM`/`TO`
+/// substring matches, ANDed together. Returns matching indices into `mails`.
+fn search(criteria: &str, mails: &[UidMail]) -> Vec<usize> {
+    let tokens: Vec<&str> = criteria.split_whitespace().collect();
+    let mut i = 0;
+    let mut subject = None;
+    let mut from = None;
+    let mut to = None;
+
+    while i < tokens.len() {
+        match tokens[i].to_ascii_uppercase().as_str() {
+            "ALL" => {
+                i += 1;
+            }
+            "SUBJECT" if i + 1 < tokens.len() => {
+                subject = Some(unquote(tokens[i + 1]));
+                i += 2;
+            }
+            "FROM" if i + 1 < tokens.len() => {
+                from = Some(unquote(tokens[i + 1]));
+                i += 2;
+            }
+            "TO" if i + 1 < tokens.len() => {
+                to = Some(unquote(tokens[i + 1]));
+                i += 2;
+            }
+            _ => {
+                i += 1;
+            }
+        }
+    }
+
+    mails
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| {
+            let parsed = entry.mail.parsed().ok();
+            let header = |f: &dyn Fn(&crate::parsed::Parsed) -> &Option<String>| {
+                parsed.as_ref().and_then(|p| f(p).clone()).unwrap_or_default()
+            };
+
+            subject
+                .as_ref()
+                .map_or(true, |needle| header(&|p| &p.subject).to_ascii_lowercase().contains(&needle.to_ascii_lowercase()))
+                && from
+                    .as_ref()
+                    .map_or(true, |needle| header(&|p| &p.from).to_ascii_lowercase().contains(&needle.to_ascii_lowercase()))
+                && to
+                    .as_ref()
+                    .map_or(true, |needle| header(&|p| &p.to).to_ascii_lowercase().contains(&needle.to_ascii_lowercase()))
+        })
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+fn unquote(s: &str) -> String {
+    s.trim_matches('"').to_owned()
+}
+
+/// Build an IMAP `ENVELOPE` structure from a mail's parsed headers.
+fn envelope(mail: &Mail) -> String {
+    let parsed = mail.parsed().ok();
+    let field = |f: &dyn Fn(&crate::parsed::Parsed) -> &Option<String>| {
+        parsed.as_ref().and_then(|p| f(p).as_deref())
+    };
+
+    format!(
+        "({} {} {} {} {} {} {} {} NIL NIL)",
+        imap_string(field(&|p| &p.date)),
+        imap_string(field(&|p| &p.subject)),
+        addr_list(field(&|p| &p.from)),
+        addr_list(field(&|p| &p.from)),
+        addr_list(field(&|p| &p.from)),
+        addr_list(field(&|p| &p.to)),
+        addr_list(field(&|p| &p.cc)),
+        "NIL",
+    )
+}
+
+fn imap_string(value: Option<&str>) -> String {
+    match value {
+        None => "NIL".to_owned(),
+        Some(s) => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+    }
+}
+
+/// Format an address-list header value as an IMAP address structure list.
+fn addr_list(value: Option<&str>) -> String {
+    let value = match value {
+        None => return "NIL".to_owned(),
+        Some(v) => v,
+    };
+
+    let parsed = match mailparse::addrparse(value) {
+        Ok(list) => list,
+        Err(_) => return "NIL".to_owned(),
+    };
+
+    let entries: Vec<String> = flatten_addrs(&parsed)
+        .into_iter()
+        .map(|(name, addr)| {
+            let (mailbox, host) = addr.split_once('@').unwrap_or((addr.as_str(), ""));
+            format!(
+                "({} NIL {} {})",
+                imap_string(name.as_deref()),
+                imap_string(Some(mailbox)),
+                imap_string(Some(host)),
+            )
+        })
+        .collect();
+
+    if entries.is_empty() {
+        "NIL".to_owned()
+    } else {
+        format!("({})", entries.join(" "))
+    }
+}
+
+fn flatten_addrs(list: &[mailparse::MailAddr]) -> Vec<(Option<String>, String)> {
+    list.iter()
+        .flat_map(|addr| match addr {
+            mailparse::MailAddr::Single(info) => vec![(info.display_name.clone(), info.addr.clone())],
+            mailparse::MailAddr::Group(group) => flatten_addrs(&group.addrs),
+        })
+        .collect()
+}
+
@@ -1,12 +1,19 @@
 use failure::Fail;
-use lazy_static::lazy_static;
 use nix::unistd::Pid;
-use regex::Regex;
+use rand::Rng;
 use serde_derive::{Deserialize, Serialize};
-use std::{env, fs, io, path, thread, time};
+use std::{env, fs, io, path, time};
 use structopt::StructOpt;
 
+pub mod imap_server;
+pub mod maildir;
+pub mod parsed;
+pub mod recipients;
+pub mod search;
 pub mod serde_pid;
+pub mod sink;
+
+use sink::{JsonSink, MailSink};
 
 /// Name of the environment variable indicating where to store mail.
 pub const ENV_MAIL_STORE_PATH: &'static str = "TRAPMAIL_STORE";
@@ -14,10 +21,10 @@ pub const ENV_MAIL_STORE_PATH: &'static str = "TRAPMAIL_STORE";
 /// Path to use in absence of `ENV_MAIL_STORE_PATH`.
 const DEFAULT_MAIL_STORE_PATH: &'static str = "/tmp";
 
-lazy_static! {
-    /// Regular expression that matches filenames generated by `Mail`.
-    static ref FILENAME_RE: Regex = Regex::new(r"trapmail_\d+_\d+_\d+.json").unwrap();
-}
+/// Name of the environment variable selecting the storage backend.
+///
+/// Recognised values are `"json"` (the default) and `"maildir"`.
+pub const ENV_MAIL_STORE_BACKEND: &'static str = "TRAPMAIL_STORE_BACKEND";
 
 /// Command-line options for the `trapmail` program.
 #[derive(Clone, Debug, Deserialize, Serialize, StructOpt)]
@@ -55,6 +62,21 @@ pub enum Error {
     /// Failure to deserialize email from store.
     #[fail(display = "Could not deserialize mail: {}", 0)]
     MailDeserialization(serde_json::Error),
+    /// Failure to parse a mail's headers and MIME structure.
+    #[fail(display = "Could not parse mail: {}", 0)]
+    MailParsing(mailparse::MailParseError),
+    /// Failure in the IMAP server's network I/O.
+    #[fail(display = "IMAP server error: {}", 0)]
+    Server(io::Error),
+    /// Failure to open the search index's on-disk directory.
+    #[fail(display = "Could not open search index directory: {}", 0)]
+    IndexOpen(tantivy::directory::error::OpenDirectoryError),
+    /// Failure within the search index itself.
+    #[fail(display = "Search index error: {}", 0)]
+    Index(tantivy::TantivyError),
+    /// Failure to parse a search query.
+    #[fail(display = "Could not parse search query: {}", 0)]
+    IndexQuery(tantivy::query::QueryParserError),
 }
 
 type Result<T> = ::std::result::Result<T, Error>;
@@ -75,41 +97,59 @@ pub struct Mail {
     pub raw_body: Vec<u8>,
     /// A microsecond-resolution UNIX timestamp of when the mail arrived.
     pub timestamp_us: u128,
+    /// The final recipient set, resolved from `cli_options.addresses` and,
+    /// if `-t` was given, the message's `To`/`Cc`/`Bcc` headers.
+    ///
+    /// `#[serde(default)]` so mail stored by older trapmail versions, which
+    /// predate this field, still loads.
+    #[serde(default)]
+    pub resolved_recipients: Vec<String>,
+    /// A random component of `file_name`, making collisions unlikely
+    /// without having to serialize calls to `Mail::new`.
+    ///
+    /// `#[serde(default)]` so mail stored by older trapmail versions, which
+    /// predate this field, still loads.
+    #[serde(default)]
+    pub unique: u32,
 }
 
 impl Mail {
     /// Create a new `Mail` using the current time and process information.
     ///
-    /// This function will sleep for a microsecond to avoid any conflicts in
-    /// naming (see `file_name`).
+    /// If `cli_options.inline_recipients` (`-t`) is set, recipients are
+    /// additionally read from the message headers and `Bcc` is stripped
+    /// from the stored `raw_body`, as a real MTA would do.
     ///
     /// # Panics
     ///
     /// Will panic if the system returns a time before the UNIX epoch.
-    pub fn new(cli_options: CliOptions, raw_body: Vec<u8>) -> Self {
-        // We always sleep a microsecond, which is probably overkill, but
-        // guarantees no collisions, ever (a millions mails a second ought
-        // to be enough for even future test cases).
-        thread::sleep(time::Duration::from_nanos(1000));
-
+    pub fn new(cli_options: CliOptions, raw_body: Vec<u8>) -> Result<Self> {
         let timestamp_us = (time::SystemTime::now().duration_since(time::UNIX_EPOCH))
             .expect("Got current before 1970; is your clock broken?")
             .as_micros();
 
-        Mail {
+        let (resolved_recipients, raw_body) = recipients::resolve(&cli_options, &raw_body)?;
+
+        Ok(Mail {
             cli_options,
             raw_body,
             pid: nix::unistd::Pid::this(),
             ppid: nix::unistd::Pid::parent(),
             timestamp_us,
-        }
+            resolved_recipients,
+            unique: rand::thread_rng().gen(),
+        })
     }
 
     /// Create a (pathless) file_name depending on the `Mail` contents.
+    ///
+    /// The leading `timestamp_us` keeps filenames sort-ordered; the
+    /// trailing `unique` token makes them collision-free even when several
+    /// `trapmail` processes deliver within the same microsecond.
     pub fn file_name(&self) -> path::PathBuf {
         format!(
-            "trapmail_{}_{}_{}.json",
-            self.timestamp_us, self.ppid, self.pid,
+            "trapmail_{}_{}_{}_{:08x}.json",
+            self.timestamp_us, self.ppid, self.pid, self.unique,
         )
         .into()
     }
@@ -122,63 +162,74 @@ impl Mail {
 }
 
 /// Mail storage.
+///
+/// Delegates the actual on-disk layout to a `MailSink`, selected via
+/// `ENV_MAIL_STORE_BACKEND`, and maintains a `search::SearchIndex` alongside
+/// it for `search`.
 #[derive(Debug)]
 pub struct MailStore {
-    /// Root path where all mail in this store gets stored.
-    root: path::PathBuf,
+    /// The backend this store writes to and reads from.
+    sink: Box<dyn MailSink>,
+    /// Search index kept in sync with `sink`.
+    index: search::SearchIndex,
 }
 
 impl MailStore {
-    /// Construct new `MailStore` with path from environment.
-    pub fn new() -> Self {
-        Self::with_root(
-            env::var(ENV_MAIL_STORE_PATH)
-                .unwrap_or(DEFAULT_MAIL_STORE_PATH.to_owned())
-                .into(),
-        )
+    /// Construct new `MailStore` with path and backend from environment.
+    pub fn new() -> Result<Self> {
+        let root = env::var(ENV_MAIL_STORE_PATH)
+            .unwrap_or(DEFAULT_MAIL_STORE_PATH.to_owned())
+            .into();
+        let backend = env::var(ENV_MAIL_STORE_BACKEND).unwrap_or_else(|_| "json".to_owned());
+        Self::with_root_and_backend(root, &backend)
     }
 
-    /// Construct new `MailStore` with explicit path.
-    pub fn with_root(root: path::PathBuf) -> Self {
-        MailStore { root }
+    /// Construct new `MailStore` with an explicit path, using the default
+    /// (JSON) backend.
+    pub fn with_root(root: path::PathBuf) -> Result<Self> {
+        Self::with_root_and_backend(root, "json")
+    }
+
+    /// Construct a new `MailStore` with an explicit path and named backend.
+    ///
+    /// `backend` must be `"json"` or `"maildir"`.
+    pub fn with_root_and_backend(root: path::PathBuf, backend: &str) -> Result<Self> {
+        let sink: Box<dyn MailSink> = match backend {
+            "maildir" => Box::new(maildir::MaildirSink::new(root.clone())?),
+            _ => Box::new(JsonSink::new(root.clone())),
+        };
+
+        // `open` just opens a reader; it does not scan `sink` or take the
+        // writer lock, so this stays safe to do from every concurrently
+        // constructed `MailStore`.
+        let index = search::SearchIndex::open(&root)?;
+
+        Ok(MailStore { sink, index })
     }
 
     /// Add a mail to the `MailStore`.
     ///
     /// Returns the path where the mail has been stored.
+    ///
+    /// This does not touch the search index: it is rebuilt lazily, from the
+    /// mail files, the next time `search` runs. That keeps concurrent
+    /// `add`s from fighting over tantivy's process-exclusive writer lock.
     pub fn add(&self, mail: &Mail) -> Result<path::PathBuf> {
-        let output_fn = self.root.join(mail.file_name());
-
-        serde_json::to_writer_pretty(fs::File::create(&output_fn).map_err(Error::Store)?, mail)
-            .map_err(Error::MailSerialization)?;
-        Ok(output_fn)
+        self.sink.add(mail)
     }
 
     /// Iterate over all mails in storage.
     ///
     /// Mails are ordered by timestamp.
-    pub fn iter_mails(&self) -> Result<impl Iterator<Item = Result<Mail>>> {
-        // Use non-functional style here, as the nested `Result`s otherwise get
-        // a bit hairy.
-        let mut paths = Vec::new();
-
-        // We read the contents of the entire directory first for sorting.
-        for dir_result in fs::read_dir(&self.root).map_err(Error::DirEnumeration)? {
-            let dir_entry = dir_result.map_err(Error::DirEnumeration)?;
-            let filename = dir_entry
-                .file_name()
-                .into_string()
-                .expect("OsString to String conversion should not fail for prefiltered filename.");
-
-            if FILENAME_RE.is_match(&filename) {
-                paths.push(filename);
-            }
-        }
-
-        // All files are named `trapmail_TIMESTAMP_..` and thus will be sorted
-        // correctly, even when sorted by filename.
-        paths.sort();
-
-        Ok(paths.into_iter().map(Mail::load))
+    pub fn iter_mails(&self) -> Result<Box<dyn Iterator<Item = Result<Mail>>>> {
+        self.sink.iter_mails()
+    }
+
+    /// Search the store for mails matching `query`.
+    ///
+    /// Rebuilds the on-disk index first if it is stale or missing.
+    pub fn search(&self, query: &search::SearchQuery) -> Result<Vec<Mail>> {
+        let mails: Vec<Mail> = self.sink.iter_mails()?.collect::<Result<Vec<Mail>>>()?;
+        self.index.search(query, &mails)
     }
 }
\ No newline at end of file
@@ -0,0 +1,153 @@
+//! Maildir storage backend.
+//!
+//! Writes each mail as a file under `new/`, named `time.pid_unique.host` as
+//! per the Maildir convention, so any Maildir-reading client or test tool
+//! (e.g. meli, aerogramme) can read trapmail's output directly.
+
+use crate::sink::MailSink;
+use crate::{recipients, CliOptions, Error, Mail, Result};
+use mailparse::MailHeaderMap;
+use std::{ffi, fs, path};
+
+/// A `MailSink` that writes messages into a Maildir at `root`.
+///
+/// Only `raw_body` and `timestamp_us` round-trip through this format:
+/// Maildir has no concept of trapmail's `cli_options`/`pid`/`ppid`/`unique`
+/// fields, so `iter_mails` fills those in with placeholder values, and
+/// recovers `resolved_recipients` from the message's `To`/`Cc` headers.
+#[derive(Debug)]
+pub struct MaildirSink {
+    root: path::PathBuf,
+}
+
+impl MaildirSink {
+    /// Construct a new `MaildirSink` rooted at `root`, creating the
+    /// `new`/`cur`/`tmp` subdirectories if they do not already exist.
+    pub fn new(root: path::PathBuf) -> Result<Self> {
+        for sub in &["new", "cur", "tmp"] {
+            fs::create_dir_all(root.join(sub)).map_err(Error::Store)?;
+        }
+        Ok(MaildirSink { root })
+    }
+
+    fn hostname() -> String {
+        nix::unistd::gethostname()
+            .ok()
+            .and_then(|name: ffi::OsString| name.into_string().ok())
+            .unwrap_or_else(|| "localhost".to_owned())
+    }
+
+    /// Build the `time.pid_unique.host` filename for `mail`.
+    ///
+    /// `subsec_us` is zero-padded so that lexicographic (filename) sort
+    /// matches chronological order within a second, and `mail.unique` (not
+    /// `subsec_us`) is the uniqueness token, so two deliveries landing in
+    /// the same microsecond from the same pid still get distinct names.
+    fn file_name(mail: &Mail) -> String {
+        let secs = mail.timestamp_us / 1_000_000;
+        let subsec_us = mail.timestamp_us % 1_000_000;
+        format!(
+            "{}.M{:06}P{}_{:08x}.{}",
+            secs,
+            subsec_us,
+            mail.pid,
+            mail.unique,
+            Self::hostname()
+        )
+    }
+}
+
+impl MailSink for MaildirSink {
+    fn add(&self, mail: &Mail) -> Result<path::PathBuf> {
+        let output_fn = self.root.join("new").join(Self::file_name(mail));
+        fs::write(&output_fn, &mail.raw_body).map_err(Error::Store)?;
+        Ok(output_fn)
+    }
+
+    fn iter_mails(&self) -> Result<Box<dyn Iterator<Item = Result<Mail>>>> {
+        let mut paths = Vec::new();
+
+        for sub in &["new", "cur"] {
+            for dir_result in fs::read_dir(self.root.join(sub)).map_err(Error::DirEnumeration)? {
+                let dir_entry = dir_result.map_err(Error::DirEnumeration)?;
+                paths.push(dir_entry.path());
+            }
+        }
+
+        // Filenames start with the delivery time in seconds, so sorting by
+        // filename also sorts by timestamp. Sort by the filename component
+        // alone, not the full path: the full path's `new`/`cur` segment
+        // would otherwise sort before the filename, grouping all of `cur`
+        // ahead of all of `new` regardless of timestamp.
+        paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+        Ok(Box::new(paths.into_iter().map(Self::load_one)))
+    }
+}
+
+impl MaildirSink {
+    /// Reconstruct a best-effort `Mail` from a Maildir entry.
+    ///
+    /// Only `raw_body` and `timestamp_us` are recovered faithfully; the
+    /// remaining fields have no Maildir representation and are filled with
+    /// placeholders, except `resolved_recipients`, which is recovered from
+    /// the message's own `To`/`Cc` headers so that `search`'s `--recipient`
+    /// filter still works against a maildir-backed store.
+    fn load_one(path: path::PathBuf) -> Result<Mail> {
+        let raw_body = fs::read(&path).map_err(Error::Load)?;
+
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        let mut name_fields = name.split('.');
+        let secs = name_fields.next().and_then(|secs| secs.parse::<u128>().ok());
+        let subsec_us = name_fields
+            .next()
+            .and_then(|unique| unique.strip_prefix('M'))
+            .and_then(|unique| unique.get(..6))
+            .and_then(|us| us.parse::<u128>().ok())
+            .unwrap_or(0);
+        let timestamp_us = secs.map(|secs| secs * 1_000_000 + subsec_us).unwrap_or(0);
+
+        let resolved_recipients = Self::recipients_from_headers(&raw_body);
+
+        Ok(Mail {
+            cli_options: CliOptions {
+                debug: false,
+                ignore_dots: false,
+                inline_recipients: false,
+                addresses: Vec::new(),
+                dump: None,
+            },
+            pid: nix::unistd::Pid::this(),
+            ppid: nix::unistd::Pid::parent(),
+            raw_body,
+            timestamp_us,
+            resolved_recipients,
+            unique: 0,
+        })
+    }
+
+    /// Collect the addresses in `raw_body`'s `To` and `Cc` headers.
+    ///
+    /// Best-effort: unparsable mail or headers yield an empty list rather
+    /// than failing the whole load.
+    fn recipients_from_headers(raw_body: &[u8]) -> Vec<String> {
+        let parsed = match mailparse::parse_mail(raw_body) {
+            Ok(parsed) => parsed,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut addrs = Vec::new();
+        for header_name in &["To", "Cc"] {
+            if let Some(value) = parsed.headers.get_first_value(header_name) {
+                if let Ok(parsed_addrs) = recipients::parse_addresses(&value) {
+                    for addr in parsed_addrs {
+                        if !addrs.contains(&addr) {
+                            addrs.push(addr);
+                        }
+                    }
+                }
+            }
+        }
+        addrs
+    }
+}
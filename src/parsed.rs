@@ -0,0 +1,73 @@
+//! Structured, parsed view of a stored `Mail`.
+//!
+//! This is built lazily from `Mail::raw_body` on demand; `raw_body` remains
+//! the on-disk source of truth.
+
+use crate::{Error, Mail, Result};
+use mailparse::MailHeaderMap;
+
+/// A single leaf part of a (possibly multipart) MIME message.
+#[derive(Debug)]
+pub struct MailPart {
+    /// The `Content-Type` of this part, e.g. `text/plain` or `image/png`.
+    pub content_type: String,
+    /// The filename taken from `Content-Disposition` or `Content-Type`, if any.
+    pub filename: Option<String>,
+    /// The decoded (transfer-encoding-stripped) body of this part.
+    pub body: Vec<u8>,
+}
+
+/// A structured view of a message's headers and MIME tree.
+#[derive(Debug)]
+pub struct Parsed {
+    /// Decoded `From` header, if present.
+    pub from: Option<String>,
+    /// Decoded `To` header, if present.
+    pub to: Option<String>,
+    /// Decoded `Cc` header, if present.
+    pub cc: Option<String>,
+    /// Decoded `Subject` header, if present.
+    pub subject: Option<String>,
+    /// Decoded `Date` header, if present.
+    pub date: Option<String>,
+    /// Every leaf part of the MIME tree, in document order.
+    pub parts: Vec<MailPart>,
+}
+
+/// Recursively collect the leaf parts of a parsed MIME tree.
+fn collect_parts(raw: &mailparse::ParsedMail) -> Vec<MailPart> {
+    if raw.subparts.is_empty() {
+        let filename = raw
+            .get_content_disposition()
+            .params
+            .get("filename")
+            .cloned()
+            .or_else(|| raw.ctype.params.get("name").cloned());
+
+        vec![MailPart {
+            content_type: raw.ctype.mimetype.clone(),
+            filename,
+            body: raw.get_body_raw().unwrap_or_default(),
+        }]
+    } else {
+        raw.subparts.iter().flat_map(collect_parts).collect()
+    }
+}
+
+impl Mail {
+    /// Parse `raw_body` into a structured view of its headers and MIME parts.
+    ///
+    /// `raw_body` remains the source of truth; each call re-parses it.
+    pub fn parsed(&self) -> Result<Parsed> {
+        let raw = mailparse::parse_mail(&self.raw_body).map_err(Error::MailParsing)?;
+
+        Ok(Parsed {
+            from: raw.headers.get_first_value("From"),
+            to: raw.headers.get_first_value("To"),
+            cc: raw.headers.get_first_value("Cc"),
+            subject: raw.headers.get_first_value("Subject"),
+            date: raw.headers.get_first_value("Date"),
+            parts: collect_parts(&raw),
+        })
+    }
+}
@@ -0,0 +1,94 @@
+//! Resolution of the final recipient set for sendmail's `-t` flag.
+
+use crate::{CliOptions, Error, Result};
+use mailparse::MailHeaderMap;
+
+/// Resolve the final recipient set for a message and strip `Bcc` from its
+/// raw headers if it was read (mirroring what a real MTA does for `-t`).
+///
+/// If `cli_options.inline_recipients` is not set, the command-line
+/// addresses are returned unchanged alongside the original `raw_body`.
+/// Otherwise, addresses are additionally collected from the `To`, `Cc`, and
+/// `Bcc` headers of `raw_body`, merged with the command-line addresses
+/// (duplicates dropped), and `Bcc` is removed from the returned body.
+pub fn resolve(cli_options: &CliOptions, raw_body: &[u8]) -> Result<(Vec<String>, Vec<u8>)> {
+    let mut recipients = cli_options.addresses.clone();
+
+    if !cli_options.inline_recipients {
+        return Ok((recipients, raw_body.to_vec()));
+    }
+
+    let parsed = mailparse::parse_mail(raw_body).map_err(Error::MailParsing)?;
+
+    for header_name in &["To", "Cc", "Bcc"] {
+        if let Some(value) = parsed.headers.get_first_value(header_name) {
+            for addr in parse_addresses(&value)? {
+                if !recipients.contains(&addr) {
+                    recipients.push(addr);
+                }
+            }
+        }
+    }
+
+    Ok((recipients, strip_bcc(raw_body)))
+}
+
+/// Parse an address-list header value into plain `user@host` addresses.
+pub(crate) fn parse_addresses(value: &str) -> Result<Vec<String>> {
+    let list = mailparse::addrparse(value).map_err(Error::MailParsing)?;
+    Ok(flatten_addrs(&list))
+}
+
+fn flatten_addrs(list: &[mailparse::MailAddr]) -> Vec<String> {
+    list.iter()
+        .flat_map(|addr| match addr {
+            mailparse::MailAddr::Single(info) => vec![info.addr.clone()],
+            mailparse::MailAddr::Group(group) => flatten_addrs(&group.addrs),
+        })
+        .collect()
+}
+
+/// Find the end of the header block: the byte offset just past the first
+/// blank line, whether it is terminated `\n\n` or (as real MUAs emit)
+/// `\r\n\r\n`. Returns `raw_body.len()` if no blank line is found.
+fn header_end(raw_body: &[u8]) -> usize {
+    let crlf = raw_body
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| pos + 4);
+    let lf = raw_body
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .map(|pos| pos + 2);
+
+    match (crlf, lf) {
+        (Some(a), Some(b)) => a.min(b),
+        (Some(a), None) => a,
+        (None, Some(b)) => b,
+        (None, None) => raw_body.len(),
+    }
+}
+
+/// Remove any `Bcc` header, including folded continuation lines, from the
+/// raw headers of a message. The body is left untouched.
+fn strip_bcc(raw_body: &[u8]) -> Vec<u8> {
+    let split_at = header_end(raw_body);
+    let (header, rest) = raw_body.split_at(split_at);
+    let header = String::from_utf8_lossy(header);
+
+    let mut out = String::new();
+    let mut skipping = false;
+    for line in header.split_inclusive('\n') {
+        let is_continuation = line.starts_with(' ') || line.starts_with('\t');
+        if !is_continuation {
+            skipping = line.len() >= 4 && line[..4].eq_ignore_ascii_case("bcc:");
+        }
+        if !skipping {
+            out.push_str(line);
+        }
+    }
+
+    let mut result = out.into_bytes();
+    result.extend_from_slice(rest);
+    result
+}
@@ -0,0 +1,247 @@
+//! A searchable index over a `MailStore`, backed by `tantivy`.
+//!
+//! The index is stored under a `.trapmail_index` directory inside the
+//! store's root. Opening it (`SearchIndex::open`) never touches a writer or
+//! scans the store, so it stays safe to do on every `MailStore`
+//! construction, including concurrent ones: tantivy's `IndexWriter` holds a
+//! process-exclusive lock, so it is only opened, briefly, from `search`
+//! itself, when the index turns out to be stale or missing and needs
+//! rebuilding from the mail files. The normal send path (`MailStore::add`)
+//! never acquires it.
+
+use crate::{Error, Mail, Result};
+use regex::escape;
+use std::path::Path;
+use structopt::StructOpt;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::{AllQuery, BooleanQuery, Occur, Query, RangeQuery, RegexQuery};
+use tantivy::schema::{Field, Schema, Value, FAST, INDEXED, STORED, STRING};
+use tantivy::{doc, Index, IndexReader, IndexWriter, ReloadPolicy};
+
+/// Name of the subdirectory (inside a `MailStore` root) holding the index.
+const INDEX_DIR_NAME: &'static str = ".trapmail_index";
+
+/// Maximum number of matches `search` returns in one call.
+const SEARCH_LIMIT: usize = 10_000;
+
+/// Parameters for a trapmail `search` subcommand / `SearchIndex::search` call.
+///
+/// `sender`/`recipient`/`subject`/`body` match as case-insensitive
+/// substrings, not tokenized words (so `--subject reset` matches a subject
+/// of `password-reset`).
+#[derive(Clone, Debug, Default, StructOpt)]
+pub struct SearchQuery {
+    /// Only match mail sent from an address containing this substring.
+    #[structopt(long)]
+    pub sender: Option<String>,
+    /// Only match mail with a recipient containing this substring.
+    #[structopt(long)]
+    pub recipient: Option<String>,
+    /// Only match mail whose subject contains this substring.
+    #[structopt(long)]
+    pub subject: Option<String>,
+    /// Only match mail whose body contains this substring.
+    #[structopt(long)]
+    pub body: Option<String>,
+    /// Only match mail received at or after this microsecond timestamp.
+    #[structopt(long)]
+    pub since_us: Option<u128>,
+    /// Only match mail received at or before this microsecond timestamp.
+    #[structopt(long)]
+    pub until_us: Option<u128>,
+}
+
+/// The set of fields the index schema is built from.
+struct Fields {
+    timestamp: Field,
+    sender: Field,
+    recipients: Field,
+    subject: Field,
+    body: Field,
+    raw_json: Field,
+}
+
+fn build_schema() -> (Schema, Fields) {
+    let mut schema_builder = Schema::builder();
+    let fields = Fields {
+        timestamp: schema_builder.add_i64_field("timestamp", INDEXED | FAST),
+        // `STRING`, not `TEXT`: fields are indexed as a single untokenized
+        // term (lowercased on write) so that `search`'s regex queries give
+        // substring semantics instead of tokenized word matching.
+        sender: schema_builder.add_text_field("sender", STRING),
+        recipients: schema_builder.add_text_field("recipients", STRING),
+        subject: schema_builder.add_text_field("subject", STRING),
+        body: schema_builder.add_text_field("body", STRING),
+        raw_json: schema_builder.add_bytes_field("raw_json", STORED),
+    };
+    (schema_builder.build(), fields)
+}
+
+/// A `tantivy`-backed index over a `MailStore`'s mail.
+pub struct SearchIndex {
+    index: Index,
+    fields: Fields,
+    reader: IndexReader,
+}
+
+impl std::fmt::Debug for SearchIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SearchIndex").finish_non_exhaustive()
+    }
+}
+
+impl SearchIndex {
+    /// Open the index under `store_root`, creating an empty one if missing.
+    ///
+    /// This only opens a reader; no writer lock is taken and no rebuild is
+    /// attempted here. Call `search` to get an up-to-date index.
+    pub fn open(store_root: &Path) -> Result<Self> {
+        let index_dir = store_root.join(INDEX_DIR_NAME);
+        std::fs::create_dir_all(&index_dir).map_err(Error::Store)?;
+
+        let (schema, fields) = build_schema();
+        let dir = tantivy::directory::MmapDirectory::open(&index_dir).map_err(Error::IndexOpen)?;
+        let index = Index::open_or_create(dir, schema).map_err(Error::Index)?;
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommitWithDelay)
+            .try_into()
+            .map_err(Error::Index)?;
+
+        Ok(SearchIndex {
+            index,
+            fields,
+            reader,
+        })
+    }
+
+    /// Run `query` against the index, rebuilding it first from `mails` if
+    /// its document count has drifted from the store (e.g. the index was
+    /// deleted, or mail was dropped in by hand).
+    ///
+    /// Staleness is detected purely by comparing document counts, so an
+    /// in-place edit or replacement of a stored mail that leaves the count
+    /// unchanged will not trigger a rebuild, and `search` may then return a
+    /// stale copy of that mail. At most `SEARCH_LIMIT` matches are returned;
+    /// if more exist, the excess are dropped and a warning is printed to
+    /// stderr rather than failing the search.
+    ///
+    /// This is the only path that opens an `IndexWriter`.
+    pub fn search(&self, query: &SearchQuery, mails: &[Mail]) -> Result<Vec<Mail>> {
+        if self.reader.searcher().num_docs() as usize != mails.len() {
+            self.rebuild(mails)?;
+        }
+
+        let searcher = self.reader.searcher();
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        if let Some(sender) = &query.sender {
+            clauses.push((Occur::Must, self.substring_query(self.fields.sender, sender)?));
+        }
+        if let Some(recipient) = &query.recipient {
+            clauses.push((
+                Occur::Must,
+                self.substring_query(self.fields.recipients, recipient)?,
+            ));
+        }
+        if let Some(subject) = &query.subject {
+            clauses.push((Occur::Must, self.substring_query(self.fields.subject, subject)?));
+        }
+        if let Some(body) = &query.body {
+            clauses.push((Occur::Must, self.substring_query(self.fields.body, body)?));
+        }
+        if query.since_us.is_some() || query.until_us.is_some() {
+            let lower = query.since_us.map(|v| v as i64).unwrap_or(i64::MIN);
+            let upper = query.until_us.map(|v| v as i64).unwrap_or(i64::MAX);
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_i64(self.fields.timestamp, lower..=upper)),
+            ));
+        }
+
+        let query: Box<dyn Query> = if clauses.is_empty() {
+            Box::new(AllQuery)
+        } else {
+            Box::new(BooleanQuery::new(clauses))
+        };
+
+        let (top_docs, total) = searcher
+            .search(&query, &(TopDocs::with_limit(SEARCH_LIMIT), Count))
+            .map_err(Error::Index)?;
+
+        if total > top_docs.len() {
+            eprintln!(
+                "warning: search matched {} mails, only returning the first {}",
+                total,
+                top_docs.len()
+            );
+        }
+
+        let mut results = Vec::with_capacity(top_docs.len());
+        for (_score, doc_address) in top_docs {
+            let retrieved = searcher.doc(doc_address).map_err(Error::Index)?;
+            if let Some(Value::Bytes(raw_json)) = retrieved.get_first(self.fields.raw_json) {
+                results.push(serde_json::from_slice(raw_json).map_err(Error::MailDeserialization)?);
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Drop and re-add every mail in `mails`.
+    ///
+    /// Opens (and releases) the process-exclusive `IndexWriter` lock, so
+    /// this must only be called from `search`, never from the send path.
+    fn rebuild(&self, mails: &[Mail]) -> Result<()> {
+        let mut writer: IndexWriter = self.index.writer(50_000_000).map_err(Error::Index)?;
+        writer.delete_all_documents().map_err(Error::Index)?;
+        for mail in mails {
+            Self::add_doc(&self.fields, &mut writer, mail)?;
+        }
+        writer.commit().map_err(Error::Index)?;
+        self.reader.reload().map_err(Error::Index)
+    }
+
+    fn add_doc(fields: &Fields, writer: &mut IndexWriter, mail: &Mail) -> Result<()> {
+        let parsed = mail.parsed().ok();
+        let sender = parsed.as_ref().and_then(|p| p.from.clone()).unwrap_or_default();
+        let subject = parsed.as_ref().and_then(|p| p.subject.clone()).unwrap_or_default();
+        let body = parsed
+            .as_ref()
+            .map(|p| {
+                p.parts
+                    .iter()
+                    .map(|part| String::from_utf8_lossy(&part.body).into_owned())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .unwrap_or_default();
+        let recipients = mail.resolved_recipients.join(" ");
+        let raw_json = serde_json::to_vec(mail).map_err(Error::MailSerialization)?;
+
+        writer
+            .add_document(doc!(
+                fields.timestamp => mail.timestamp_us as i64,
+                fields.sender => sender.to_ascii_lowercase(),
+                fields.recipients => recipients.to_ascii_lowercase(),
+                fields.subject => subject.to_ascii_lowercase(),
+                fields.body => body.to_ascii_lowercase(),
+                fields.raw_json => raw_json,
+            ))
+            .map_err(Error::Index)?;
+
+        Ok(())
+    }
+
+    /// Build a case-insensitive substring query over a `STRING` field.
+    ///
+    /// Fields are indexed as a single lowercased term, so `.*needle.*`
+    /// matches wherever `needle` occurs, not just on token boundaries.
+    fn substring_query(&self, field: Field, needle: &str) -> Result<Box<dyn Query>> {
+        let pattern = format!(".*{}.*", escape(&needle.to_ascii_lowercase()));
+        RegexQuery::from_pattern(&pattern, field)
+            .map(|query| Box::new(query) as Box<dyn Query>)
+            .map_err(Error::Index)
+    }
+}
@@ -0,0 +1,78 @@
+//! Storage backends for trapped mail.
+//!
+//! `MailStore` delegates the actual on-disk layout to a `MailSink`, so new
+//! formats can be added without touching the lookup/iteration logic that
+//! callers rely on.
+
+use crate::{Error, Mail, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{fmt, fs, path};
+
+lazy_static! {
+    /// Regular expression that matches filenames generated by `JsonSink`,
+    /// including those written before the trailing `unique` token existed.
+    static ref FILENAME_RE: Regex = Regex::new(r"trapmail_\d+_\d+_\d+(_[0-9a-f]+)?\.json").unwrap();
+}
+
+/// A place `Mail` can be stored to and enumerated from.
+pub trait MailSink: fmt::Debug {
+    /// Add a mail to the sink, returning the path it was stored at.
+    fn add(&self, mail: &Mail) -> Result<path::PathBuf>;
+
+    /// Iterate over all mails currently in the sink.
+    ///
+    /// Mails are ordered by timestamp.
+    fn iter_mails(&self) -> Result<Box<dyn Iterator<Item = Result<Mail>>>>;
+}
+
+/// Stores each mail as a pretty-printed JSON file named
+/// `trapmail_TS_PPID_PID_UNIQUE.json` under `root`.
+///
+/// This is the original, default trapmail storage format.
+#[derive(Debug)]
+pub struct JsonSink {
+    root: path::PathBuf,
+}
+
+impl JsonSink {
+    /// Construct a new `JsonSink` rooted at `root`.
+    pub fn new(root: path::PathBuf) -> Self {
+        JsonSink { root }
+    }
+}
+
+impl MailSink for JsonSink {
+    fn add(&self, mail: &Mail) -> Result<path::PathBuf> {
+        let output_fn = self.root.join(mail.file_name());
+
+        serde_json::to_writer_pretty(fs::File::create(&output_fn).map_err(Error::Store)?, mail)
+            .map_err(Error::MailSerialization)?;
+        Ok(output_fn)
+    }
+
+    fn iter_mails(&self) -> Result<Box<dyn Iterator<Item = Result<Mail>>>> {
+        // Use non-functional style here, as the nested `Result`s otherwise get
+        // a bit hairy.
+        let mut paths = Vec::new();
+
+        // We read the contents of the entire directory first for sorting.
+        for dir_result in fs::read_dir(&self.root).map_err(Error::DirEnumeration)? {
+            let dir_entry = dir_result.map_err(Error::DirEnumeration)?;
+            let filename = dir_entry
+                .file_name()
+                .into_string()
+                .expect("OsString to String conversion should not fail for prefiltered filename.");
+
+            if FILENAME_RE.is_match(&filename) {
+                paths.push(self.root.join(filename));
+            }
+        }
+
+        // All files are named `trapmail_TIMESTAMP_..` and thus will be sorted
+        // correctly, even when sorted by filename.
+        paths.sort();
+
+        Ok(Box::new(paths.into_iter().map(Mail::load)))
+    }
+}